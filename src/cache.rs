@@ -0,0 +1,297 @@
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::net::ToSocketAddrs;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::{NixosUpgradeConfig, NixosUpgradeError};
+
+fn default_substituter() -> String {
+    "https://cache.nixos.org".to_string()
+}
+
+fn default_threshold() -> f64 {
+    0.9
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CacheCheckConfig {
+    #[serde(default = "default_substituter")]
+    substituter: String,
+
+    #[serde(default = "default_threshold")]
+    threshold: f64,
+}
+
+impl Default for CacheCheckConfig {
+    fn default() -> Self {
+        Self {
+            substituter: default_substituter(),
+            threshold: default_threshold(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CacheReport {
+    pub(crate) hits: usize,
+    pub(crate) total: usize,
+}
+
+impl CacheReport {
+    fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.hits as f64 / self.total as f64
+        }
+    }
+}
+
+fn substituter_host(substituter: &str) -> Result<String, NixosUpgradeError> {
+    let without_scheme = substituter
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(substituter);
+
+    let host = without_scheme
+        .split('/')
+        .next()
+        .unwrap_or(without_scheme)
+        .to_string();
+
+    if host.is_empty() {
+        return Err(NixosUpgradeError::CacheUnreachable(format!(
+            "could not determine host from substituter URL: {}",
+            substituter
+        )));
+    }
+
+    Ok(host)
+}
+
+fn resolve_cache_host(substituter: &str) -> Result<(), NixosUpgradeError> {
+    let host = substituter_host(substituter)?;
+
+    (host.as_str(), 443_u16)
+        .to_socket_addrs()
+        .map_err(|e| {
+            NixosUpgradeError::CacheUnreachable(format!("DNS resolution of {} failed: {}", host, e))
+        })?
+        .next()
+        .ok_or_else(|| {
+            NixosUpgradeError::CacheUnreachable(format!(
+                "DNS resolution of {} returned nothing",
+                host
+            ))
+        })?;
+
+    Ok(())
+}
+
+/// Runs a `dry-build` of the target generation and collects the store paths
+/// nix reports it would need to fetch, without building or switching. Flake
+/// input updates and lock-file commits are suppressed for the probe itself,
+/// since those happen during evaluation (before the dry build stops us) and
+/// must not fire on a cycle where the upgrade ends up being deferred.
+fn target_store_paths(config: &NixosUpgradeConfig) -> Result<Vec<String>, NixosUpgradeError> {
+    let mut probe_config = config.clone();
+    probe_config.operation = "dry-build".to_string();
+    probe_config.update_inputs = Vec::new();
+    probe_config.commit_lock_file = false;
+
+    let mut cmd = crate::build_nixos_rebuild_command(&probe_config);
+
+    debug!("Running cache readiness probe: {:?}", cmd);
+
+    let output = cmd.output().map_err(NixosUpgradeError::NixosRebuild)?;
+
+    if !output.status.success() {
+        return Err(NixosUpgradeError::NixosRebuildFailed(output.status));
+    }
+
+    let combined = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    Ok(parse_fetch_plan(&combined))
+}
+
+/// Parses the store paths listed under nix's "this/these path(s) will be
+/// fetched" section of a `--dry-run` report. The plural form always inlines
+/// the count (e.g. "these 3 paths will be fetched (42.00 MiB download, ...):"),
+/// so we match on the phrase rather than a literal prefix.
+fn parse_fetch_plan(output: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut in_fetch_section = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.contains("path will be fetched") || trimmed.contains("paths will be fetched") {
+            in_fetch_section = true;
+            continue;
+        }
+
+        if in_fetch_section {
+            if trimmed.starts_with("/nix/store/") {
+                paths.push(trimmed.to_string());
+            } else if !trimmed.is_empty() {
+                in_fetch_section = false;
+            }
+        }
+    }
+
+    paths
+}
+
+fn store_path_hash(path: &str) -> Option<&str> {
+    let name = path.strip_prefix("/nix/store/")?;
+    let (hash, _rest) = name.split_once('-')?;
+
+    if hash.len() == 32 {
+        Some(hash)
+    } else {
+        None
+    }
+}
+
+fn narinfo_is_cached(substituter: &str, hash: &str) -> bool {
+    let url = format!("{}/{}.narinfo", substituter.trim_end_matches('/'), hash);
+
+    match ureq::head(&url).call() {
+        Ok(response) => response.status() == 200,
+        Err(ureq::Error::Status(status, _)) => status == 200,
+        Err(e) => {
+            debug!("Failed to query {}: {}", url, e);
+            false
+        }
+    }
+}
+
+/// Estimates cache readiness for the target generation's closure by
+/// concurrently probing each store path's narinfo on the configured
+/// substituter.
+pub(crate) fn check_cache_readiness(
+    cache_check: &CacheCheckConfig,
+    config: &NixosUpgradeConfig,
+) -> Result<CacheReport, NixosUpgradeError> {
+    resolve_cache_host(&cache_check.substituter)?;
+
+    let paths = target_store_paths(config)?;
+    let hashes: Vec<&str> = paths.iter().filter_map(|p| store_path_hash(p)).collect();
+
+    if hashes.is_empty() {
+        info!("Cache readiness: nothing needs to be fetched, closure is fully cached");
+        return Ok(CacheReport { hits: 0, total: 0 });
+    }
+
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for hash in &hashes {
+            let tx = tx.clone();
+            let substituter = cache_check.substituter.clone();
+            let hash = hash.to_string();
+
+            scope.spawn(move || {
+                let hit = narinfo_is_cached(&substituter, &hash);
+                let _ = tx.send(hit);
+            });
+        }
+    });
+
+    drop(tx);
+
+    let hits = rx.iter().filter(|hit| *hit).count();
+    let total = hashes.len();
+
+    info!(
+        "Cache readiness: {}/{} store paths present on {}",
+        hits, total, cache_check.substituter
+    );
+
+    Ok(CacheReport { hits, total })
+}
+
+pub(crate) fn is_ready(report: &CacheReport, cache_check: &CacheCheckConfig) -> bool {
+    report.ratio() >= cache_check.threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_fetch_plan, substituter_host};
+
+    #[test]
+    fn extracts_host_from_a_url_with_scheme_and_path() {
+        assert_eq!(
+            substituter_host("https://cache.nixos.org").unwrap(),
+            "cache.nixos.org"
+        );
+        assert_eq!(
+            substituter_host("https://cache.example.com/some/path").unwrap(),
+            "cache.example.com"
+        );
+    }
+
+    #[test]
+    fn extracts_host_from_a_bare_host_without_scheme() {
+        assert_eq!(
+            substituter_host("cache.nixos.org").unwrap(),
+            "cache.nixos.org"
+        );
+    }
+
+    #[test]
+    fn rejects_a_url_with_no_host() {
+        assert!(substituter_host("https://").is_err());
+    }
+
+    #[test]
+    fn parses_multi_path_fetch_plan_with_inlined_count() {
+        let output = "\
+these derivations will be built:
+  /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-foo.drv
+these 3 paths will be fetched (42.00 MiB download, 128.00 MiB unpacked):
+  /nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-bar
+  /nix/store/cccccccccccccccccccccccccccccccc-baz
+  /nix/store/dddddddddddddddddddddddddddddddd-qux
+building '/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-system.drv'...
+";
+
+        let paths = parse_fetch_plan(output);
+
+        assert_eq!(
+            paths,
+            vec![
+                "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-bar",
+                "/nix/store/cccccccccccccccccccccccccccccccc-baz",
+                "/nix/store/dddddddddddddddddddddddddddddddd-qux",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_single_path_fetch_plan() {
+        let output = "\
+this path will be fetched (1.00 MiB download, 2.00 MiB unpacked):
+  /nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-bar
+";
+
+        let paths = parse_fetch_plan(output);
+
+        assert_eq!(
+            paths,
+            vec!["/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-bar"]
+        );
+    }
+
+    #[test]
+    fn returns_empty_when_nothing_needs_fetching() {
+        let output = "these derivations will be built:\n  /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-foo.drv\n";
+
+        assert!(parse_fetch_plan(output).is_empty());
+    }
+}
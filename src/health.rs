@@ -0,0 +1,163 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+use crate::NixosUpgradeError;
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HealthCheckConfig {
+    #[serde(default)]
+    commands: Vec<String>,
+
+    #[serde(default, rename = "systemdUnits")]
+    systemd_units: Vec<String>,
+
+    #[serde(default = "default_timeout_secs", rename = "timeoutSecs")]
+    timeout_secs: u64,
+}
+
+struct CheckFailure {
+    check: String,
+    output: String,
+}
+
+fn run_with_timeout(timeout_secs: u64, cmd: Command) -> Result<(bool, String), NixosUpgradeError> {
+    let mut timed = Command::new("timeout");
+    timed.arg(timeout_secs.to_string());
+    timed.arg(cmd.get_program());
+    timed.args(cmd.get_args());
+
+    let output = timed
+        .output()
+        .map_err(|e| NixosUpgradeError::CommandSpawn {
+            program: "timeout".to_string(),
+            source: e,
+        })?;
+
+    let combined = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    Ok((output.status.success(), combined.trim().to_string()))
+}
+
+fn check_command(
+    command: &str,
+    timeout_secs: u64,
+) -> Result<Option<CheckFailure>, NixosUpgradeError> {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]);
+
+    let (ok, output) = run_with_timeout(timeout_secs, cmd)?;
+
+    if ok {
+        debug!("Health check command passed: {}", command);
+        Ok(None)
+    } else {
+        Ok(Some(CheckFailure {
+            check: command.to_string(),
+            output,
+        }))
+    }
+}
+
+fn check_systemd_unit(
+    unit: &str,
+    timeout_secs: u64,
+) -> Result<Option<CheckFailure>, NixosUpgradeError> {
+    let mut cmd = Command::new("systemctl");
+    cmd.args(["is-active", unit]);
+
+    let (_, output) = run_with_timeout(timeout_secs, cmd)?;
+
+    if output == "active" {
+        debug!("Health check unit is active: {}", unit);
+        Ok(None)
+    } else {
+        Ok(Some(CheckFailure {
+            check: format!("systemd unit {}", unit),
+            output,
+        }))
+    }
+}
+
+/// Runs every configured health command and systemd unit check after a
+/// `switch` or `boot` upgrade, returning the first failure encountered.
+/// `operation` decides how a failure is rolled back: `switch` performs a
+/// live `nixos-rebuild switch --rollback`, while `boot` only reinstates the
+/// currently-booted (known-good) generation as the boot default, since the
+/// running system was never touched in that mode.
+pub(crate) fn verify(config: &HealthCheckConfig, operation: &str) -> Result<(), NixosUpgradeError> {
+    if !matches!(operation, "switch" | "boot") {
+        warn!(
+            "healthCheck is configured but operation is '{}', skipping post-upgrade verification",
+            operation
+        );
+        return Ok(());
+    }
+
+    for command in &config.commands {
+        if let Some(failure) = check_command(command, config.timeout_secs)? {
+            return fail(failure, operation);
+        }
+    }
+
+    for unit in &config.systemd_units {
+        if let Some(failure) = check_systemd_unit(unit, config.timeout_secs)? {
+            return fail(failure, operation);
+        }
+    }
+
+    info!("All post-upgrade health checks passed");
+    Ok(())
+}
+
+fn fail(failure: CheckFailure, operation: &str) -> Result<(), NixosUpgradeError> {
+    warn!(
+        "Health check '{}' failed, rolling back: {}",
+        failure.check, failure.output
+    );
+    rollback(operation)?;
+
+    Err(NixosUpgradeError::HealthCheckFailed {
+        check: failure.check,
+        output: failure.output,
+    })
+}
+
+fn rollback(operation: &str) -> Result<(), NixosUpgradeError> {
+    if operation == "switch" {
+        info!("Rolling back to the previous generation");
+
+        let status = Command::new("nixos-rebuild")
+            .args(["switch", "--rollback"])
+            .status()
+            .map_err(NixosUpgradeError::NixosRebuild)?;
+
+        if !status.success() {
+            return Err(NixosUpgradeError::NixosRebuildFailed(status));
+        }
+    } else {
+        info!("Reverting boot default to the currently booted generation");
+
+        let status = Command::new("/run/booted-system/bin/switch-to-configuration")
+            .arg("boot")
+            .status()
+            .map_err(|e| NixosUpgradeError::CommandSpawn {
+                program: "switch-to-configuration".to_string(),
+                source: e,
+            })?;
+
+        if !status.success() {
+            return Err(NixosUpgradeError::NixosRebuildFailed(status));
+        }
+    }
+
+    Ok(())
+}
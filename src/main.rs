@@ -1,16 +1,24 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use log::{debug, error, info, warn};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::net::{SocketAddr, TcpStream};
 use std::path::PathBuf;
 use std::process::{Command, ExitStatus};
-use std::time::Duration;
 use thiserror::Error;
 
+mod cache;
+mod health;
+mod network;
+mod reboot;
+
+use cache::{check_cache_readiness, is_ready, CacheCheckConfig};
+use health::HealthCheckConfig;
+use network::NetworkCheckConfig;
+use reboot::{check_and_reboot_if_needed, is_within_reboot_window, RebootWindow};
+
 #[derive(Error, Debug)]
-enum NixosUpgradeError {
+pub(crate) enum NixosUpgradeError {
     #[error("Failed to check network connectivity: {0}")]
     NetworkCheck(#[source] std::io::Error),
 
@@ -20,6 +28,13 @@ enum NixosUpgradeError {
     #[error("Failed to execute nixos-rebuild: {0}")]
     NixosRebuild(#[source] std::io::Error),
 
+    #[error("Failed to execute {program}: {source}")]
+    CommandSpawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("nixos-rebuild failed with exit code: {0}")]
     NixosRebuildFailed(ExitStatus),
 
@@ -28,22 +43,34 @@ enum NixosUpgradeError {
 
     #[error("Failed to parse config file: {0}")]
     ConfigParse(#[source] serde_json::Error),
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RebootWindow {
-    lower: String,
-    upper: String,
+    #[error("Failed to write reboot marker file: {0}")]
+    RebootMarkerWrite(#[source] std::io::Error),
+
+    #[error("Binary cache is unreachable: {0}")]
+    CacheUnreachable(String),
+
+    #[error("Binary cache is not ready: only {hits}/{total} store paths are cached")]
+    CacheNotReady { hits: usize, total: usize },
+
+    #[error("Health check '{check}' failed after upgrade, rolled back to previous generation. Output: {output}")]
+    HealthCheckFailed { check: String, output: String },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct NixosUpgradeConfig {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NixosUpgradeConfig {
     #[serde(default)]
     operation: String,
 
     #[serde(default)]
     flake: Option<String>,
 
+    #[serde(default, rename = "updateInputs")]
+    update_inputs: Vec<String>,
+
+    #[serde(default, rename = "commitLockFile")]
+    commit_lock_file: bool,
+
     #[serde(default)]
     channel: Option<String>,
 
@@ -55,6 +82,15 @@ struct NixosUpgradeConfig {
 
     #[serde(default, rename = "rebootWindow")]
     reboot_window: Option<RebootWindow>,
+
+    #[serde(default, rename = "cacheCheck")]
+    cache_check: Option<CacheCheckConfig>,
+
+    #[serde(default, rename = "networkCheck")]
+    network_check: NetworkCheckConfig,
+
+    #[serde(default, rename = "healthCheck")]
+    health_check: Option<HealthCheckConfig>,
 }
 
 impl Default for NixosUpgradeConfig {
@@ -62,10 +98,15 @@ impl Default for NixosUpgradeConfig {
         Self {
             operation: "boot".to_string(),
             flake: None,
+            update_inputs: Vec::new(),
+            commit_lock_file: false,
             channel: None,
             flags: vec!["--no-build-output".to_string()],
             allow_reboot: false,
             reboot_window: None,
+            cache_check: None,
+            network_check: NetworkCheckConfig::default(),
+            health_check: None,
         }
     }
 }
@@ -78,60 +119,14 @@ struct Cli {
 
     #[clap(short, long)]
     verbose: bool,
-}
-
-fn check_network_available() -> Result<bool, NixosUpgradeError> {
-    let dns_servers = ["8.8.8.8:53", "1.1.1.1:53"];
-
-    let mut last_error = None;
-
-    for server in dns_servers {
-        match server.parse::<SocketAddr>() {
-            Ok(addr) => match TcpStream::connect_timeout(&addr, Duration::from_secs(2)) {
-                Ok(_) => {
-                    info!("Network connectivity confirmed via {}", server);
-                    return Ok(true);
-                }
-                Err(e) => {
-                    debug!("Failed to connect to {}: {}", server, e);
-                    last_error = Some(e);
-                }
-            },
-            Err(e) => debug!("Failed to parse address {}: {}", server, e),
-        }
-    }
 
-    warn!("No network connectivity detected");
-
-    if let Some(err) = last_error {
-        return Err(NixosUpgradeError::NetworkCheck(err));
-    }
-
-    Ok(false)
+    /// Run the full decision pipeline and report what would happen, without
+    /// invoking nixos-rebuild or shutdown.
+    #[clap(long)]
+    dry_run: bool,
 }
 
-fn is_within_reboot_window(window: &RebootWindow) -> Result<bool> {
-    let output = Command::new("date")
-        .args(["+%H:%M"])
-        .output()
-        .context("Failed to get current time")?;
-
-    let current_time = String::from_utf8(output.stdout)
-        .context("Failed to parse current time")?
-        .trim()
-        .to_string();
-
-    let lower = &window.lower;
-    let upper = &window.upper;
-
-    if lower < upper {
-        Ok(current_time > *lower && current_time < *upper)
-    } else {
-        Ok(current_time < *upper || current_time > *lower)
-    }
-}
-
-fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeError> {
+pub(crate) fn build_nixos_rebuild_command(config: &NixosUpgradeConfig) -> Command {
     let mut cmd = Command::new("nixos-rebuild");
 
     cmd.arg(&config.operation);
@@ -142,6 +137,14 @@ fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeErro
 
     if let Some(flake) = &config.flake {
         cmd.args(["--refresh", "--flake", flake]);
+
+        for input in &config.update_inputs {
+            cmd.args(["--update-input", input]);
+        }
+
+        if config.commit_lock_file {
+            cmd.arg("--commit-lock-file");
+        }
     }
 
     if let Some(channel) = &config.channel {
@@ -152,6 +155,23 @@ fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeErro
         cmd.arg(flag);
     }
 
+    cmd
+}
+
+fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeError> {
+    if let Some(cache_check) = &config.cache_check {
+        let report = check_cache_readiness(cache_check, config)?;
+
+        if !is_ready(&report, cache_check) {
+            return Err(NixosUpgradeError::CacheNotReady {
+                hits: report.hits,
+                total: report.total,
+            });
+        }
+    }
+
+    let mut cmd = build_nixos_rebuild_command(config);
+
     debug!("Running command: {:?}", cmd);
 
     let status = cmd.status().map_err(NixosUpgradeError::NixosRebuild)?;
@@ -160,6 +180,10 @@ fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeErro
         return Err(NixosUpgradeError::NixosRebuildFailed(status));
     }
 
+    if let Some(health_check) = &config.health_check {
+        health::verify(health_check, &config.operation)?;
+    }
+
     if config.allow_reboot && config.operation == "boot" {
         check_and_reboot_if_needed(config)?;
     }
@@ -167,44 +191,37 @@ fn run_nixos_upgrade(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeErro
     Ok(())
 }
 
-fn check_and_reboot_if_needed(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeError> {
-    let booted = Command::new("readlink")
-        .args([
-            "-f",
-            "/run/booted-system/kernel",
-            "/run/booted-system/initrd",
-            "/run/booted-system/kernel-modules",
-        ])
-        .output()
-        .map_err(NixosUpgradeError::NixosRebuild)?;
-
-    let built = Command::new("readlink")
-        .args([
-            "-f",
-            "/nix/var/nix/profiles/system/kernel",
-            "/nix/var/nix/profiles/system/initrd",
-            "/nix/var/nix/profiles/system/kernel-modules",
-        ])
-        .output()
-        .map_err(NixosUpgradeError::NixosRebuild)?;
-
-    if booted.stdout != built.stdout {
-        if let Some(window) = &config.reboot_window {
-            if let Ok(can_reboot) = is_within_reboot_window(window) {
-                if !can_reboot {
-                    info!("Outside of configured reboot window, skipping reboot.");
-                    return Ok(());
+fn report_dry_run(config: &NixosUpgradeConfig) -> Result<(), NixosUpgradeError> {
+    let cmd = build_nixos_rebuild_command(config);
+
+    info!("Dry run: would execute: {:?}", cmd);
+
+    if config.allow_reboot && config.operation == "boot" {
+        match reboot::diff_reason() {
+            Ok(Some(reason)) => {
+                info!("Dry run: {}", reason);
+                if let Some(window) = &config.reboot_window {
+                    match is_within_reboot_window(window) {
+                        Ok(true) => info!(
+                            "Dry run: reboot is needed and current time is within the reboot window, a reboot would be triggered"
+                        ),
+                        Ok(false) => info!(
+                            "Dry run: reboot is needed but current time is outside the reboot window, reboot would be skipped"
+                        ),
+                        Err(e) => warn!(
+                            "Dry run: failed to check reboot window ({}), reboot would proceed",
+                            e
+                        ),
+                    }
+                } else {
+                    info!("Dry run: reboot is needed and no reboot window is configured, a reboot would be triggered");
                 }
-            } else {
-                warn!("Failed to check reboot window, proceeding with reboot.");
             }
+            Ok(None) => info!("Dry run: no reboot would be needed"),
+            Err(e) => warn!("Dry run: failed to determine reboot need: {}", e),
         }
-
-        info!("Initiating reboot since kernel, initrd or modules have changed");
-        Command::new("shutdown")
-            .args(["-r", "+1", "NixOS upgrade requires reboot"])
-            .status()
-            .map_err(NixosUpgradeError::NixosRebuild)?;
+    } else {
+        info!("Dry run: reboot is not enabled for this operation, no reboot would be triggered");
     }
 
     Ok(())
@@ -237,14 +254,117 @@ fn main() -> Result<()> {
 
     debug!("Using configuration: {:?}", config);
 
-    if !check_network_available()? {
+    if !network::check_network_available(&config.network_check)? {
         warn!("Network is not available, skipping upgrade");
         return Err(NixosUpgradeError::NetworkUnavailable.into());
     }
 
+    if cli.dry_run {
+        info!("Dry run: network is available");
+        report_dry_run(&config)?;
+        return Ok(());
+    }
+
     info!("Running NixOS upgrade with operation: {}", config.operation);
-    run_nixos_upgrade(&config).context("Failed to upgrade NixOS")?;
 
-    info!("NixOS upgrade completed successfully");
-    Ok(())
+    match run_nixos_upgrade(&config) {
+        Ok(()) => {
+            info!("NixOS upgrade completed successfully");
+            Ok(())
+        }
+        Err(NixosUpgradeError::CacheNotReady { hits, total }) => {
+            warn!(
+                "Binary cache is not ready ({}/{} store paths cached), skipping upgrade this cycle",
+                hits, total
+            );
+            std::process::exit(75);
+        }
+        Err(e) => Err(e).context("Failed to upgrade NixOS"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_nixos_rebuild_command, NixosUpgradeConfig};
+
+    fn args(config: &NixosUpgradeConfig) -> Vec<String> {
+        build_nixos_rebuild_command(config)
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn channel_mode_upgrades_and_passes_no_flake_flags() {
+        let config = NixosUpgradeConfig::default();
+
+        assert_eq!(
+            args(&config),
+            vec!["boot", "--upgrade", "--no-build-output"]
+        );
+    }
+
+    #[test]
+    fn flake_mode_refreshes_instead_of_upgrading() {
+        let config = NixosUpgradeConfig {
+            flake: Some("/etc/nixos#myhost".to_string()),
+            ..NixosUpgradeConfig::default()
+        };
+
+        assert_eq!(
+            args(&config),
+            vec![
+                "boot",
+                "--refresh",
+                "--flake",
+                "/etc/nixos#myhost",
+                "--no-build-output"
+            ]
+        );
+    }
+
+    #[test]
+    fn flake_mode_appends_update_input_and_commit_lock_file_flags() {
+        let config = NixosUpgradeConfig {
+            flake: Some("/etc/nixos#myhost".to_string()),
+            update_inputs: vec!["nixpkgs".to_string(), "home-manager".to_string()],
+            commit_lock_file: true,
+            ..NixosUpgradeConfig::default()
+        };
+
+        assert_eq!(
+            args(&config),
+            vec![
+                "boot",
+                "--refresh",
+                "--flake",
+                "/etc/nixos#myhost",
+                "--update-input",
+                "nixpkgs",
+                "--update-input",
+                "home-manager",
+                "--commit-lock-file",
+                "--no-build-output"
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_override_adds_nix_path_flag() {
+        let config = NixosUpgradeConfig {
+            channel: Some("https://channels.nixos.org/nixos-24.05".to_string()),
+            ..NixosUpgradeConfig::default()
+        };
+
+        assert_eq!(
+            args(&config),
+            vec![
+                "boot",
+                "--upgrade",
+                "-I",
+                "nixpkgs=https://channels.nixos.org/nixos-24.05/nixexprs.tar.xz",
+                "--no-build-output"
+            ]
+        );
+    }
 }
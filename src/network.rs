@@ -0,0 +1,153 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::NixosUpgradeError;
+
+fn default_host() -> String {
+    "cache.nixos.org".to_string()
+}
+
+fn default_port() -> u16 {
+    443
+}
+
+fn default_timeout_secs() -> u64 {
+    2
+}
+
+fn default_fallback_dns_servers() -> Vec<String> {
+    vec!["8.8.8.8:53".to_string(), "1.1.1.1:53".to_string()]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NetworkCheckConfig {
+    #[serde(default = "default_host")]
+    host: String,
+
+    #[serde(default = "default_port")]
+    port: u16,
+
+    #[serde(default = "default_timeout_secs", rename = "timeoutSecs")]
+    timeout_secs: u64,
+
+    #[serde(
+        default = "default_fallback_dns_servers",
+        rename = "fallbackDnsServers"
+    )]
+    fallback_dns_servers: Vec<String>,
+}
+
+impl Default for NetworkCheckConfig {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            port: default_port(),
+            timeout_secs: default_timeout_secs(),
+            fallback_dns_servers: default_fallback_dns_servers(),
+        }
+    }
+}
+
+/// Resolves the configured host and attempts a TCP connection to it,
+/// falling back to a raw DNS-server reachability check if resolution or
+/// the connection attempt fails.
+pub(crate) fn check_network_available(
+    config: &NetworkCheckConfig,
+) -> Result<bool, NixosUpgradeError> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+
+    match resolve_and_connect(&config.host, config.port, timeout) {
+        Ok(true) => return Ok(true),
+        Ok(false) => warn!(
+            "Could not reach {}:{}, falling back to DNS server check",
+            config.host, config.port
+        ),
+        Err(e) => warn!(
+            "Failed to resolve or connect to {}:{} ({}), falling back to DNS server check",
+            config.host, config.port, e
+        ),
+    }
+
+    check_dns_servers(&config.fallback_dns_servers, timeout)
+}
+
+fn resolve_and_connect(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<bool, NixosUpgradeError> {
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(NixosUpgradeError::NetworkCheck)?
+        .collect();
+
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => {
+                info!("Network connectivity confirmed via {} ({})", host, addr);
+                return Ok(true);
+            }
+            Err(e) => debug!("Failed to connect to {} ({}): {}", host, addr, e),
+        }
+    }
+
+    Ok(false)
+}
+
+fn check_dns_servers(servers: &[String], timeout: Duration) -> Result<bool, NixosUpgradeError> {
+    let mut last_error = None;
+
+    for server in servers {
+        match server.parse::<SocketAddr>() {
+            Ok(addr) => match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(_) => {
+                    info!("Network connectivity confirmed via {}", server);
+                    return Ok(true);
+                }
+                Err(e) => {
+                    debug!("Failed to connect to {}: {}", server, e);
+                    last_error = Some(e);
+                }
+            },
+            Err(e) => debug!("Failed to parse address {}: {}", server, e),
+        }
+    }
+
+    warn!("No network connectivity detected");
+
+    if let Some(err) = last_error {
+        return Err(NixosUpgradeError::NetworkCheck(err));
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_and_connect;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    #[test]
+    fn returns_true_when_the_host_is_reachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let reachable = resolve_and_connect("127.0.0.1", port, Duration::from_millis(200)).unwrap();
+
+        assert!(reachable);
+    }
+
+    #[test]
+    fn returns_false_when_the_port_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let reachable = resolve_and_connect("127.0.0.1", port, Duration::from_millis(200)).unwrap();
+
+        assert!(!reachable);
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::process::Command;
+
+use crate::{NixosUpgradeConfig, NixosUpgradeError};
+
+const BOOTED_SYSTEM: &str = "/run/booted-system";
+const BUILT_SYSTEM: &str = "/nix/var/nix/profiles/system";
+const REBOOT_MARKER: &str = "/var/run/reboot-required";
+
+const COMPONENTS: &[(&str, &str)] = &[
+    ("kernel", "kernel"),
+    ("initrd", "initrd"),
+    ("kernel-modules", "kernel modules"),
+    ("systemd", "systemd"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RebootWindow {
+    lower: String,
+    upper: String,
+}
+
+pub(crate) fn is_within_reboot_window(window: &RebootWindow) -> Result<bool> {
+    let output = Command::new("date")
+        .args(["+%H:%M"])
+        .output()
+        .context("Failed to get current time")?;
+
+    let current_time = String::from_utf8(output.stdout)
+        .context("Failed to parse current time")?
+        .trim()
+        .to_string();
+
+    let lower = &window.lower;
+    let upper = &window.upper;
+
+    if lower < upper {
+        Ok(current_time > *lower && current_time < *upper)
+    } else {
+        Ok(current_time < *upper || current_time > *lower)
+    }
+}
+
+fn resolve_component(system: &str, component: &str) -> Result<String, NixosUpgradeError> {
+    let output = Command::new("readlink")
+        .args(["-f", &format!("{}/{}", system, component)])
+        .output()
+        .map_err(|e| NixosUpgradeError::CommandSpawn {
+            program: "readlink".to_string(),
+            source: e,
+        })?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn nixos_version(system: &str) -> Option<String> {
+    fs::read_to_string(format!("{}/nixos-version", system))
+        .ok()
+        .map(|v| v.trim().to_string())
+}
+
+/// Compares the booted and built system closures component by component and
+/// returns a human-readable reason (e.g. "kernel and initrd changed") if a
+/// reboot is needed, or `None` if the two systems are equivalent.
+pub(crate) fn diff_reason() -> Result<Option<String>, NixosUpgradeError> {
+    if let (Some(booted), Some(built)) = (nixos_version(BOOTED_SYSTEM), nixos_version(BUILT_SYSTEM))
+    {
+        if booted == built {
+            return Ok(None);
+        }
+    }
+
+    let mut changed = Vec::new();
+
+    for (component, label) in COMPONENTS {
+        let booted = resolve_component(BOOTED_SYSTEM, component)?;
+        let built = resolve_component(BUILT_SYSTEM, component)?;
+
+        if booted != built {
+            changed.push(*label);
+        }
+    }
+
+    if changed.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(format!("{} changed", join_reasons(&changed))))
+}
+
+fn join_reasons(reasons: &[&str]) -> String {
+    match reasons {
+        [] => String::new(),
+        [only] => only.to_string(),
+        [first, second] => format!("{} and {}", first, second),
+        [init @ .., last] => format!("{} and {}", init.join(", "), last),
+    }
+}
+
+fn write_reboot_marker(reason: &str) -> Result<(), NixosUpgradeError> {
+    fs::write(REBOOT_MARKER, format!("{}\n", reason)).map_err(NixosUpgradeError::RebootMarkerWrite)
+}
+
+pub(crate) fn check_and_reboot_if_needed(
+    config: &NixosUpgradeConfig,
+) -> Result<(), NixosUpgradeError> {
+    if let Some(reason) = diff_reason()? {
+        info!("Reboot required: {}", reason);
+        write_reboot_marker(&reason)?;
+
+        if let Some(window) = &config.reboot_window {
+            if let Ok(can_reboot) = is_within_reboot_window(window) {
+                if !can_reboot {
+                    info!("Outside of configured reboot window, skipping reboot.");
+                    return Ok(());
+                }
+            } else {
+                warn!("Failed to check reboot window, proceeding with reboot.");
+            }
+        }
+
+        info!("Initiating reboot: {}", reason);
+        Command::new("shutdown")
+            .args(["-r", "+1", "NixOS upgrade requires reboot"])
+            .status()
+            .map_err(|e| NixosUpgradeError::CommandSpawn {
+                program: "shutdown".to_string(),
+                source: e,
+            })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::join_reasons;
+
+    #[test]
+    fn joins_a_single_reason() {
+        assert_eq!(join_reasons(&["kernel"]), "kernel");
+    }
+
+    #[test]
+    fn joins_two_reasons_with_and() {
+        assert_eq!(join_reasons(&["kernel", "initrd"]), "kernel and initrd");
+    }
+
+    #[test]
+    fn joins_three_or_more_reasons_with_a_comma_list_and_trailing_and() {
+        assert_eq!(
+            join_reasons(&["kernel", "initrd", "kernel modules"]),
+            "kernel, initrd and kernel modules"
+        );
+        assert_eq!(
+            join_reasons(&["kernel", "initrd", "kernel modules", "systemd"]),
+            "kernel, initrd, kernel modules and systemd"
+        );
+    }
+
+    #[test]
+    fn joins_no_reasons_as_an_empty_string() {
+        assert_eq!(join_reasons(&[]), "");
+    }
+}